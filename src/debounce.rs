@@ -0,0 +1,128 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::stream::Stream;
+use tokio::time::{Duration, Instant};
+
+use crate::{PostponableDelay, PostponableDelayHandle, PostponeDelayResponse};
+
+/// Debounces `stream`: each item resets a `quiet_period` timer, and only the
+/// most recently received item is yielded once that period elapses without a
+/// new one arriving.
+pub fn debounce<S: Stream + Unpin>(stream: S, quiet_period: Duration) -> Debounce<S> {
+    Debounce {
+        stream,
+        quiet_period,
+        timer: None,
+        buffered: None,
+        stream_done: false,
+    }
+}
+
+/// A `Stream` adapter that debounces its inner stream. See [`debounce`].
+pub struct Debounce<S: Stream> {
+    stream: S,
+    quiet_period: Duration,
+    timer: Option<(PostponableDelay, PostponableDelayHandle)>,
+    buffered: Option<S::Item>,
+    stream_done: bool,
+}
+
+// Neither the buffered item nor the timer is ever pinned in place.
+impl<S: Stream + Unpin> Unpin for Debounce<S> {}
+
+impl<S: Stream + Unpin> Debounce<S> {
+    fn arm(&mut self, target: Instant) {
+        // earlier is fine here: an item always pushes the quiet period
+        // forward relative to itself, but a burst of items arriving out of
+        // poll order must still be able to rearm.
+        //
+        // `reschedule` can report `AlreadyResolved` if the existing timer
+        // resolved without `self.timer` having been cleared yet; fall back
+        // to arming a fresh one rather than relying on that never happening.
+        let needs_new_timer = match &self.timer {
+            Some((_, handle)) => {
+                handle.reschedule(target) == PostponeDelayResponse::AlreadyResolved
+            }
+            None => true,
+        };
+        if needs_new_timer {
+            let delay = PostponableDelay::new(target);
+            let handle = delay.get_handle();
+            self.timer = Some((delay, handle));
+        }
+    }
+}
+
+impl<S: Stream + Unpin> Stream for Debounce<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<S::Item>> {
+        let this = &mut *self;
+        loop {
+            if !this.stream_done {
+                match Pin::new(&mut this.stream).poll_next(cx) {
+                    Poll::Ready(Some(item)) => {
+                        this.buffered = Some(item);
+                        this.arm(Instant::now() + this.quiet_period);
+                        continue;
+                    }
+                    Poll::Ready(None) => {
+                        this.stream_done = true;
+                        this.timer = None;
+                        if let Some(item) = this.buffered.take() {
+                            return Poll::Ready(Some(item));
+                        }
+                        return Poll::Ready(None);
+                    }
+                    Poll::Pending => {}
+                }
+            }
+            match &mut this.timer {
+                None if this.stream_done => return Poll::Ready(None),
+                None => return Poll::Pending,
+                Some((delay, _)) => match Pin::new(delay).poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(()) => {
+                        this.timer = None;
+                        return Poll::Ready(this.buffered.take());
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::stream::StreamExt;
+    use tokio::sync::mpsc;
+
+    const ERROR_MARGIN: std::time::Duration = std::time::Duration::from_millis(3);
+
+    #[tokio::test]
+    async fn emits_once_per_quiet_period() {
+        let (mut tx, rx) = mpsc::channel(8);
+        // Held open past the quiet period so the emission below can only
+        // come from the debounce timer firing, not from the stream ending.
+        let _keep_alive = tx.clone();
+        tokio::spawn(async move {
+            tx.send(1).await.unwrap();
+            tokio::time::delay_for(ERROR_MARGIN).await;
+            tx.send(2).await.unwrap();
+            tokio::time::delay_for(ERROR_MARGIN).await;
+            tx.send(3).await.unwrap();
+        });
+        let mut debounced = debounce(rx, 4 * ERROR_MARGIN);
+        assert_eq!(debounced.next().await, Some(3));
+    }
+
+    #[tokio::test]
+    async fn flushes_buffered_item_on_stream_end() {
+        let mut debounced = debounce(tokio::stream::iter(vec![1, 2, 3]), 4 * ERROR_MARGIN);
+        assert_eq!(debounced.next().await, Some(3));
+        assert_eq!(debounced.next().await, None);
+    }
+}
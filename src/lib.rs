@@ -2,43 +2,61 @@ use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 
+use futures::task::AtomicWaker;
+use tokio::time::Instant;
+
+mod debounce;
+mod queue;
+pub use debounce::{debounce, Debounce};
+pub use queue::{Key, PostponableDelayQueue};
+
+/// State shared between a `PostponableDelay` and the handles used to postpone it
+struct Shared {
+    target: Mutex<(Instant, bool)>,
+    waker: AtomicWaker,
+}
+
 /// Similar to `tokio::time::Delay`,
 /// but you can push back the moment when this future will resolve
 pub struct PostponableDelay {
     delay: tokio::time::Delay,
-    target: Arc<Mutex<(std::time::Instant, bool)>>,
+    /// The deadline `delay` is currently armed for; compared against the
+    /// shared target on every poll so a postponement that lands while the
+    /// task is parked re-arms the timer immediately, rather than waiting for
+    /// the stale deadline to elapse first.
+    armed: Instant,
+    shared: Arc<Shared>,
 }
 
 impl PostponableDelay {
-    /// Returns a future that will resolve no sooner than `instant`
-    pub fn new(instant: std::time::Instant) -> Self {
-        let target = instant.into();
+    /// Returns a future that will resolve no sooner than `instant`, unless a
+    /// handle's `reschedule` or `resolve_now` moves the resolution earlier
+    pub fn new(instant: Instant) -> Self {
         PostponableDelay {
-            delay: tokio::time::delay_until(target),
-            target: Arc::new(Mutex::new((instant, false))),
+            delay: tokio::time::delay_until(instant),
+            armed: instant,
+            shared: Arc::new(Shared {
+                target: Mutex::new((instant, false)),
+                waker: AtomicWaker::new(),
+            }),
         }
     }
 
     /// Returns a handle to allow pushing back the future's resolution
     pub fn get_handle(&self) -> PostponableDelayHandle {
         PostponableDelayHandle {
-            target: self.target.clone(),
+            shared: self.shared.clone(),
         }
     }
 
-    fn project(
-        &mut self,
-    ) -> (
-        Pin<&mut tokio::time::Delay>,
-        &Mutex<(std::time::Instant, bool)>,
-    ) {
-        (Pin::new(&mut self.delay), &self.target)
+    fn project(&mut self) -> (Pin<&mut tokio::time::Delay>, &Arc<Shared>) {
+        (Pin::new(&mut self.delay), &self.shared)
     }
 }
 
 /// A handle to postpone a `ResettableDelay`'s resolution
 pub struct PostponableDelayHandle {
-    target: Arc<Mutex<(std::time::Instant, bool)>>,
+    shared: Arc<Shared>,
 }
 
 /// The result of a postpone request
@@ -65,38 +83,104 @@ impl PostponeDelayResponse {
 impl PostponableDelayHandle {
     /// Attempts to postopone the corresponding `PostponableDelay`'s resolution,
     /// returns a `PostponeDelayResponse` detailing if it succeeded.
+    ///
+    /// Unlike [`reschedule`](PostponableDelayHandle::reschedule), this refuses
+    /// any `target` earlier than the current one: the resolution is
+    /// guaranteed to never move closer.
     #[must_use]
-    pub fn postpone(&self, target: std::time::Instant) -> PostponeDelayResponse {
-        let mut guard = self.target.lock().unwrap();
+    pub fn postpone(&self, target: Instant) -> PostponeDelayResponse {
+        let mut guard = self.shared.target.lock().unwrap();
         let previous_target = guard.0;
         if guard.1 {
             PostponeDelayResponse::AlreadyResolved
         } else if target < previous_target {
             PostponeDelayResponse::CantResolveEarlier
         } else {
-            *&mut guard.0 = target;
+            guard.0 = target;
+            std::mem::drop(guard);
+            self.shared.waker.wake();
             PostponeDelayResponse::Ok
         }
     }
+
+    /// Moves the corresponding `PostponableDelay`'s resolution to `target`,
+    /// which may be earlier or later than the current one.
+    ///
+    /// The only way this can fail is if the delay has already resolved.
+    #[must_use]
+    pub fn reschedule(&self, target: Instant) -> PostponeDelayResponse {
+        let mut guard = self.shared.target.lock().unwrap();
+        if guard.1 {
+            PostponeDelayResponse::AlreadyResolved
+        } else {
+            guard.0 = target;
+            std::mem::drop(guard);
+            self.shared.waker.wake();
+            PostponeDelayResponse::Ok
+        }
+    }
+
+    /// Forces the corresponding `PostponableDelay` to resolve as soon as it
+    /// is next polled.
+    #[must_use]
+    pub fn resolve_now(&self) -> PostponeDelayResponse {
+        self.reschedule(Instant::now())
+    }
+
+    /// Returns the instant at which the corresponding `PostponableDelay` is
+    /// currently due to resolve.
+    pub fn deadline(&self) -> Instant {
+        self.shared.target.lock().unwrap().0
+    }
+
+    /// Returns `true` if the corresponding `PostponableDelay`'s deadline has
+    /// passed, regardless of whether the future has been polled since.
+    pub fn is_elapsed(&self) -> bool {
+        let guard = self.shared.target.lock().unwrap();
+        guard.1 || guard.0 <= Instant::now()
+    }
+
+    /// Returns how long is left before the corresponding `PostponableDelay`'s
+    /// deadline, or `None` once it has passed.
+    pub fn remaining(&self) -> Option<std::time::Duration> {
+        let guard = self.shared.target.lock().unwrap();
+        let now = Instant::now();
+        if guard.1 || guard.0 <= now {
+            None
+        } else {
+            Some(guard.0 - now)
+        }
+    }
 }
 
 impl std::future::Future for PostponableDelay {
     type Output = ();
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let (delay, target) = self.project();
-        match delay.poll(cx) {
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(_) => {
-                let mut guard = target.lock().unwrap();
-                let target = guard.0;
-                if target <= std::time::Instant::now() {
-                    guard.1 = true;
-                    Poll::Ready(())
-                } else {
-                    std::mem::drop(guard);
-                    self.delay = tokio::time::delay_until(target.into());
-                    self.poll(cx)
+        self.shared.waker.register(cx.waker());
+        loop {
+            let (resolved, target) = {
+                let guard = self.shared.target.lock().unwrap();
+                (guard.1, guard.0)
+            };
+            if resolved {
+                return Poll::Ready(());
+            }
+            if target != self.armed {
+                self.delay.reset(target);
+                self.armed = target;
+            }
+            let (delay, shared) = self.project();
+            match delay.poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(_) => {
+                    let mut guard = shared.target.lock().unwrap();
+                    if guard.0 <= Instant::now() {
+                        guard.1 = true;
+                        return Poll::Ready(());
+                    }
+                    // the target moved again in the race between arming and
+                    // firing; loop around to re-arm for its new value
                 }
             }
         }
@@ -108,10 +192,10 @@ const ERROR_MARGIN: std::time::Duration = std::time::Duration::from_millis(3);
 
 #[tokio::test]
 async fn no_resets() {
-    let target = std::time::Instant::now() + 4 * ERROR_MARGIN;
+    let target = Instant::now() + 4 * ERROR_MARGIN;
     std::thread::sleep(2 * ERROR_MARGIN);
     PostponableDelay::new(target).await;
-    let end = std::time::Instant::now();
+    let end = Instant::now();
     println!("{:?}", end - target);
     assert!(target <= end);
     assert!(end <= target + ERROR_MARGIN);
@@ -119,20 +203,57 @@ async fn no_resets() {
 
 #[tokio::test]
 async fn with_resets() {
-    let target = std::time::Instant::now() + 4 * ERROR_MARGIN;
+    let target = Instant::now() + 4 * ERROR_MARGIN;
     let delay = PostponableDelay::new(target);
     let handle = delay.get_handle();
     std::thread::sleep(2 * ERROR_MARGIN);
-    let target = std::time::Instant::now() + 4 * ERROR_MARGIN;
+    let target = Instant::now() + 4 * ERROR_MARGIN;
     handle.postpone(target).unwrap();
     assert_eq!(
         handle.postpone(target - ERROR_MARGIN),
         PostponeDelayResponse::CantResolveEarlier
     );
     delay.await;
-    let end = std::time::Instant::now();
+    let end = Instant::now();
     println!("{:?}", end - target);
     assert_eq!(handle.postpone(end), PostponeDelayResponse::AlreadyResolved);
     assert!(target <= end);
     assert!(end <= target + ERROR_MARGIN);
 }
+
+#[tokio::test]
+async fn reschedule_earlier() {
+    let far_target = Instant::now() + 20 * ERROR_MARGIN;
+    let delay = PostponableDelay::new(far_target);
+    let handle = delay.get_handle();
+    let near_target = Instant::now() + 4 * ERROR_MARGIN;
+    handle.reschedule(near_target).unwrap();
+    delay.await;
+    let end = Instant::now();
+    assert!(near_target <= end);
+    assert!(end <= near_target + ERROR_MARGIN);
+}
+
+#[tokio::test]
+async fn resolve_now_forces_resolution() {
+    let target = Instant::now() + 20 * ERROR_MARGIN;
+    let delay = PostponableDelay::new(target);
+    let handle = delay.get_handle();
+    handle.resolve_now().unwrap();
+    let start = Instant::now();
+    delay.await;
+    assert!(Instant::now() - start < ERROR_MARGIN);
+}
+
+#[tokio::test]
+async fn query_api() {
+    let target = Instant::now() + 4 * ERROR_MARGIN;
+    let delay = PostponableDelay::new(target);
+    let handle = delay.get_handle();
+    assert_eq!(handle.deadline(), target);
+    assert!(!handle.is_elapsed());
+    assert!(handle.remaining().unwrap() <= 4 * ERROR_MARGIN);
+    delay.await;
+    assert!(handle.is_elapsed());
+    assert_eq!(handle.remaining(), None);
+}
@@ -0,0 +1,238 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use tokio::time::{Delay, Instant};
+
+use crate::PostponeDelayResponse;
+
+/// A key identifying a value inserted into a [`PostponableDelayQueue`]
+pub type Key = u64;
+
+struct Entry<T> {
+    value: T,
+    deadline: Instant,
+}
+
+/// Like [`PostponableDelay`](crate::PostponableDelay), but for many
+/// independently postponable deadlines at once.
+///
+/// Values are inserted with a deadline and a [`Key`] is returned; that key
+/// can later be used to [`postpone`](PostponableDelayQueue::postpone) or
+/// [`reschedule`](PostponableDelayQueue::reschedule) the value's deadline.
+/// Expired values are yielded in deadline order through
+/// [`poll_expired`](PostponableDelayQueue::poll_expired), or by polling the
+/// queue as a `Stream`.
+pub struct PostponableDelayQueue<T> {
+    entries: HashMap<Key, Entry<T>>,
+    heap: BinaryHeap<Reverse<(Instant, Key)>>,
+    next_key: Key,
+    delay: Option<Delay>,
+    armed: Option<Instant>,
+    waker: Option<Waker>,
+}
+
+// Values are never pinned: they are only moved in and out of `entries`.
+impl<T> Unpin for PostponableDelayQueue<T> {}
+
+impl<T> Default for PostponableDelayQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> PostponableDelayQueue<T> {
+    /// Creates an empty queue
+    pub fn new() -> Self {
+        PostponableDelayQueue {
+            entries: HashMap::new(),
+            heap: BinaryHeap::new(),
+            next_key: 0,
+            delay: None,
+            armed: None,
+            waker: None,
+        }
+    }
+
+    /// Inserts `value`, due to expire no sooner than `deadline` unless
+    /// [`reschedule`](PostponableDelayQueue::reschedule) later moves it
+    /// earlier, and returns the key that identifies it
+    pub fn insert(&mut self, value: T, deadline: Instant) -> Key {
+        let key = self.next_key;
+        self.next_key += 1;
+        self.entries.insert(key, Entry { value, deadline });
+        self.heap.push(Reverse((deadline, key)));
+        self.wake_if_sooner(deadline);
+        key
+    }
+
+    /// Attempts to postpone `key`'s expiry to `target`, refusing any `target`
+    /// earlier than its current deadline. See
+    /// [`PostponableDelayHandle::postpone`](crate::PostponableDelayHandle::postpone).
+    #[must_use]
+    pub fn postpone(&mut self, key: Key, target: Instant) -> PostponeDelayResponse {
+        self.reschedule_impl(key, target, true)
+    }
+
+    /// Moves `key`'s expiry to `target`, which may be earlier or later than
+    /// its current deadline. See
+    /// [`PostponableDelayHandle::reschedule`](crate::PostponableDelayHandle::reschedule).
+    #[must_use]
+    pub fn reschedule(&mut self, key: Key, target: Instant) -> PostponeDelayResponse {
+        self.reschedule_impl(key, target, false)
+    }
+
+    fn reschedule_impl(
+        &mut self,
+        key: Key,
+        target: Instant,
+        monotonic: bool,
+    ) -> PostponeDelayResponse {
+        match self.entries.get_mut(&key) {
+            None => PostponeDelayResponse::AlreadyResolved,
+            Some(entry) if monotonic && target < entry.deadline => {
+                PostponeDelayResponse::CantResolveEarlier
+            }
+            Some(entry) => {
+                entry.deadline = target;
+                self.heap.push(Reverse((target, key)));
+                self.wake_if_sooner(target);
+                PostponeDelayResponse::Ok
+            }
+        }
+    }
+
+    fn wake_if_sooner(&mut self, deadline: Instant) {
+        if self.armed.map_or(true, |armed| deadline < armed) {
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Polls the queue for the next expired value, in deadline order.
+    ///
+    /// Returns `Poll::Pending` when nothing is currently due, even if the
+    /// queue is empty: unlike a regular stream, more values may still be
+    /// inserted later.
+    pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<T> {
+        loop {
+            while let Some(&Reverse((deadline, key))) = self.heap.peek() {
+                match self.entries.get(&key) {
+                    Some(entry) if entry.deadline == deadline => break,
+                    _ => {
+                        self.heap.pop();
+                    }
+                }
+            }
+            let next = match self.heap.peek() {
+                Some(&Reverse((deadline, _))) => deadline,
+                None => {
+                    // Nothing armed until the next insert: forget the last
+                    // deadline so a later insert always wakes us, even if
+                    // its deadline happens to be later than this stale one.
+                    self.armed = None;
+                    self.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            };
+            if self.armed != Some(next) {
+                match &mut self.delay {
+                    Some(delay) => delay.reset(next),
+                    None => self.delay = Some(tokio::time::delay_until(next)),
+                }
+                self.armed = Some(next);
+            }
+            match Pin::new(self.delay.as_mut().unwrap()).poll(cx) {
+                Poll::Pending => {
+                    // Kept so `wake_if_sooner` can wake us when an earlier
+                    // deadline than `next` is inserted or rescheduled.
+                    self.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+                Poll::Ready(_) => {
+                    if next <= Instant::now() {
+                        let Reverse((_, key)) = self.heap.pop().unwrap();
+                        if let Some(entry) = self.entries.remove(&key) {
+                            return Poll::Ready(entry.value);
+                        }
+                    }
+                    // the timer fired before `next` is due by the clock;
+                    // loop around to re-arm
+                }
+            }
+        }
+    }
+}
+
+impl<T> tokio::stream::Stream for PostponableDelayQueue<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.poll_expired(cx).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::stream::StreamExt;
+
+    const ERROR_MARGIN: std::time::Duration = std::time::Duration::from_millis(3);
+
+    #[tokio::test]
+    async fn yields_in_deadline_order() {
+        let mut queue = PostponableDelayQueue::new();
+        let now = Instant::now();
+        queue.insert("second", now + 4 * ERROR_MARGIN);
+        queue.insert("first", now + 2 * ERROR_MARGIN);
+        assert_eq!(queue.next().await, Some("first"));
+        assert_eq!(queue.next().await, Some("second"));
+    }
+
+    #[tokio::test]
+    async fn postpone_and_reschedule() {
+        let mut queue = PostponableDelayQueue::new();
+        let now = Instant::now();
+        let key = queue.insert("value", now + 2 * ERROR_MARGIN);
+        assert_eq!(
+            queue.postpone(key, now),
+            PostponeDelayResponse::CantResolveEarlier
+        );
+        queue.reschedule(key, now).unwrap();
+        let start = Instant::now();
+        assert_eq!(queue.next().await, Some("value"));
+        assert!(Instant::now() - start < ERROR_MARGIN);
+    }
+
+    #[tokio::test]
+    async fn wakes_for_inserts_after_draining_empty() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::task::{Context, Wake, Waker};
+
+        struct CountingWaker(AtomicUsize);
+        impl Wake for CountingWaker {
+            fn wake(self: std::sync::Arc<Self>) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let mut queue: PostponableDelayQueue<&str> = PostponableDelayQueue::new();
+        queue.insert("first", Instant::now());
+        assert_eq!(queue.next().await, Some("first"));
+
+        // Park the queue on its now-empty heap, exactly as an idle consumer
+        // loop would between items.
+        let waker = std::sync::Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let cx_waker = Waker::from(waker.clone());
+        let mut cx = Context::from_waker(&cx_waker);
+        assert!(queue.poll_expired(&mut cx).is_pending());
+
+        // A later deadline than any seen before must still wake the parked
+        // consumer, not just earlier ones.
+        queue.insert("second", Instant::now() + ERROR_MARGIN);
+        assert_eq!(waker.0.load(Ordering::SeqCst), 1);
+    }
+}